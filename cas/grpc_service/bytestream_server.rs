@@ -0,0 +1,228 @@
+// Copyright 2020 Nathan (Blaise) Bruer.  All rights reserved.
+
+use std::convert::TryFrom;
+use std::io::Read;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::mpsc as std_mpsc;
+
+use async_stream::try_stream;
+use futures_core::Stream;
+use tonic::{Request, Response, Status, Streaming};
+
+use proto::google::bytestream::{
+    byte_stream_server::ByteStream, byte_stream_server::ByteStreamServer as Server,
+    QueryWriteStatusRequest, QueryWriteStatusResponse, ReadRequest, ReadResponse, WriteRequest,
+    WriteResponse,
+};
+use store::Store;
+
+use super::cas_server::io_error_to_status;
+
+/// Blobs read from the store are chunked back into messages of at most this
+/// many bytes so a single `ReadResponse` never holds an entire large blob.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Bridges the `Streaming<WriteRequest>` bytes coming off the wire into a
+/// `std::io::Read` that `Store::update` can consume. The Write RPC forwards
+/// each chunk into the channel as it arrives off the stream; this side is
+/// driven on a blocking task so the async runtime is never blocked waiting
+/// on it.
+struct ChannelReader {
+    receiver: std_mpsc::Receiver<std::io::Result<Vec<u8>>>,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelReader {
+    fn new(receiver: std_mpsc::Receiver<std::io::Result<Vec<u8>>>) -> Self {
+        ChannelReader {
+            receiver,
+            buffer: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.buffer.len() {
+            match self.receiver.recv() {
+                Ok(Ok(chunk)) => {
+                    self.buffer = chunk;
+                    self.pos = 0;
+                }
+                Ok(Err(e)) => return Err(e), // Write aborted: fail the store update.
+                Err(_) => return Ok(0), // Sender dropped: end of stream.
+            }
+        }
+        let n = std::cmp::min(buf.len(), self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Parses a `resource_name` of the form `blobs/{hash}/{size}` (Read) or
+/// `uploads/{uuid}/blobs/{hash}/{size}` (Write) into `(hash, size_bytes)`.
+fn parse_blob_resource_name(resource_name: &str) -> Result<(String, usize), Status> {
+    let parts: Vec<&str> = resource_name.split('/').collect();
+    let blobs_pos = parts
+        .iter()
+        .position(|&part| part == "blobs")
+        .ok_or_else(|| Status::invalid_argument("resource_name is missing a 'blobs' segment"))?;
+    let hash = parts
+        .get(blobs_pos + 1)
+        .ok_or_else(|| Status::invalid_argument("resource_name is missing a hash"))?;
+    let size_bytes = parts
+        .get(blobs_pos + 2)
+        .ok_or_else(|| Status::invalid_argument("resource_name is missing a size"))?
+        .parse::<usize>()
+        .map_err(|_| Status::invalid_argument("resource_name size was not a number"))?;
+    Ok((hash.to_string(), size_bytes))
+}
+
+#[derive(Debug)]
+pub struct ByteStreamServer {
+    store: Arc<dyn Store>,
+}
+
+impl ByteStreamServer {
+    pub fn new(store: Arc<dyn Store>) -> Self {
+        ByteStreamServer { store }
+    }
+
+    pub fn into_service(self) -> Server<ByteStreamServer> {
+        Server::new(self)
+    }
+}
+
+#[tonic::async_trait]
+impl ByteStream for ByteStreamServer {
+    type ReadStream =
+        Pin<Box<dyn Stream<Item = Result<ReadResponse, Status>> + Send + Sync + 'static>>;
+
+    async fn read(
+        &self,
+        grpc_request: Request<ReadRequest>,
+    ) -> Result<Response<Self::ReadStream>, Status> {
+        let read_request = grpc_request.into_inner();
+        let (hash, size_bytes) = parse_blob_resource_name(&read_request.resource_name)?;
+        let read_offset = usize::try_from(read_request.read_offset)
+            .map_err(|_| Status::invalid_argument("read_offset was negative"))?;
+        let read_limit = if read_request.read_limit > 0 {
+            Some(read_request.read_limit as usize)
+        } else {
+            None
+        };
+        let store = self.store.clone();
+
+        let output = try_stream! {
+            let mut data = Vec::new();
+            store
+                .get_part(&hash, size_bytes, &mut data, read_offset, read_limit)
+                .await
+                .map_err(io_error_to_status)?;
+            for chunk in data.chunks(READ_CHUNK_SIZE) {
+                yield ReadResponse {
+                    data: chunk.to_vec(),
+                };
+            }
+        };
+        Ok(Response::new(Box::pin(output)))
+    }
+
+    async fn write(
+        &self,
+        grpc_request: Request<Streaming<WriteRequest>>,
+    ) -> Result<Response<WriteResponse>, Status> {
+        let mut stream = grpc_request.into_inner();
+        let first_request = stream
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("Write stream closed before any data"))?;
+        let (hash, size_bytes) = parse_blob_resource_name(&first_request.resource_name)?;
+
+        let (tx, rx) = std_mpsc::channel::<std::io::Result<Vec<u8>>>();
+        let store = self.store.clone();
+        let hash_for_commit = hash.clone();
+        let update_task = tokio::task::spawn_blocking(move || {
+            tokio::runtime::Handle::current().block_on(store.update(
+                &hash_for_commit,
+                size_bytes,
+                Box::new(ChannelReader::new(rx)),
+            ))
+        });
+
+        let mut committed_size: i64 = 0;
+        let mut request = first_request;
+        loop {
+            if request.write_offset != committed_size {
+                return Err(Status::invalid_argument(format!(
+                    "Out of order write_offset, expected {} got {}",
+                    committed_size, request.write_offset
+                )));
+            }
+            if !request.data.is_empty() {
+                committed_size += request.data.len() as i64;
+                tx.send(Ok(request.data))
+                    .map_err(|_| Status::internal("Store commit task exited early"))?;
+            }
+            if request.finish_write {
+                break;
+            }
+            request = match stream.message().await? {
+                Some(request) => request,
+                None => {
+                    // The client went away (or forgot finish_write) before
+                    // committing the blob. Drop the partial bytes instead of
+                    // letting `update_task` commit a truncated blob under the
+                    // full declared size_bytes.
+                    let _ = tx.send(Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "Write stream closed before finish_write",
+                    )));
+                    drop(tx);
+                    let _ = update_task.await;
+                    return Err(Status::aborted(format!(
+                        "Write stream for {} closed before finish_write ({} of {} bytes received)",
+                        hash, committed_size, size_bytes
+                    )));
+                }
+            };
+        }
+        drop(tx);
+
+        if committed_size as usize != size_bytes {
+            let _ = update_task.await;
+            return Err(Status::invalid_argument(format!(
+                "WriteRequest for {} declared size_bytes {} but received {} bytes",
+                hash, size_bytes, committed_size
+            )));
+        }
+
+        update_task
+            .await
+            .map_err(|e| Status::internal(format!("Write task panicked: {:?}", e)))?
+            .map_err(|e| Status::internal(format!("Error committing blob to store: {:?}", e)))?;
+
+        Ok(Response::new(WriteResponse { committed_size }))
+    }
+
+    async fn query_write_status(
+        &self,
+        grpc_request: Request<QueryWriteStatusRequest>,
+    ) -> Result<Response<QueryWriteStatusResponse>, Status> {
+        let request = grpc_request.into_inner();
+        let (hash, size_bytes) = parse_blob_resource_name(&request.resource_name)?;
+        let complete = self
+            .store
+            .has(&hash, size_bytes)
+            .await
+            .map_err(io_error_to_status)?;
+        Ok(Response::new(QueryWriteStatusResponse {
+            committed_size: if complete { size_bytes as i64 } else { 0 },
+            complete,
+        }))
+    }
+}
@@ -0,0 +1,50 @@
+// Copyright 2020 Nathan (Blaise) Bruer.  All rights reserved.
+
+use tonic::{Request, Response, Status};
+
+use proto::build::bazel::remote::execution::v2::{
+    capabilities_server::Capabilities, capabilities_server::CapabilitiesServer as Server,
+    digest_function, CacheCapabilities, GetCapabilitiesRequest, ServerCapabilities,
+};
+
+#[derive(Debug)]
+pub struct CapabilitiesServer {
+    max_batch_total_size_bytes: i64,
+}
+
+impl CapabilitiesServer {
+    pub fn new(max_batch_total_size_bytes: usize) -> Self {
+        CapabilitiesServer {
+            max_batch_total_size_bytes: max_batch_total_size_bytes as i64,
+        }
+    }
+
+    pub fn into_service(self) -> Server<CapabilitiesServer> {
+        Server::new(self)
+    }
+}
+
+#[tonic::async_trait]
+impl Capabilities for CapabilitiesServer {
+    async fn get_capabilities(
+        &self,
+        _grpc_request: Request<GetCapabilitiesRequest>,
+    ) -> Result<Response<ServerCapabilities>, Status> {
+        let response = ServerCapabilities {
+            cache_capabilities: Some(CacheCapabilities {
+                digest_function: vec![digest_function::Value::Sha256 as i32],
+                action_cache_update_capabilities: None,
+                cache_priority_capabilities: None,
+                max_batch_total_size_bytes: self.max_batch_total_size_bytes,
+                symlink_absolute_path_strategy: 0,
+                supported_compressors: vec![],
+                supported_batch_update_compressors: vec![],
+            }),
+            execution_capabilities: None,
+            deprecated_api_version: None,
+            low_api_version: None,
+            high_api_version: None,
+        };
+        Ok(Response::new(response))
+    }
+}
@@ -2,21 +2,27 @@
 
 #![feature(try_blocks)]
 
+use std::collections::{HashSet, VecDeque};
 use std::convert::TryFrom;
 use std::io::Cursor;
 use std::pin::Pin;
+use std::sync::Arc;
 
+use async_stream::try_stream;
 use futures_core::Stream;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use prost::Message;
 use tokio::io::Error;
 use tonic::{Code, Request, Response, Status};
 
 use macros::{error_if, make_err};
 use proto::build::bazel::remote::execution::v2::{
-    batch_update_blobs_response, content_addressable_storage_server::ContentAddressableStorage,
+    batch_read_blobs_response, batch_update_blobs_response,
+    content_addressable_storage_server::ContentAddressableStorage,
     content_addressable_storage_server::ContentAddressableStorageServer as Server,
     BatchReadBlobsRequest, BatchReadBlobsResponse, BatchUpdateBlobsRequest,
-    BatchUpdateBlobsResponse, FindMissingBlobsRequest, FindMissingBlobsResponse, GetTreeRequest,
-    GetTreeResponse,
+    BatchUpdateBlobsResponse, Digest, Directory, FindMissingBlobsRequest,
+    FindMissingBlobsResponse, GetTreeRequest, GetTreeResponse,
 };
 use store::Store;
 
@@ -24,31 +30,33 @@ use store::Store;
 // use tonic::Code;
 use proto::google::rpc::Status as GrpcStatus;
 use std::result::Result;
-fn result_to_status(result: Result<(), Error>) -> GrpcStatus {
+
+fn kind_to_code(kind: &tokio::io::ErrorKind) -> Code {
     use tokio::io::ErrorKind;
-    fn kind_to_code(kind: &ErrorKind) -> Code {
-        match kind {
-            ErrorKind::NotFound => Code::NotFound,
-            ErrorKind::PermissionDenied => Code::PermissionDenied,
-            ErrorKind::ConnectionRefused => Code::Unavailable,
-            ErrorKind::ConnectionReset => Code::Unavailable,
-            ErrorKind::ConnectionAborted => Code::Unavailable,
-            ErrorKind::NotConnected => Code::Internal,
-            ErrorKind::AddrInUse => Code::Internal,
-            ErrorKind::AddrNotAvailable => Code::Internal,
-            ErrorKind::BrokenPipe => Code::Internal,
-            ErrorKind::AlreadyExists => Code::AlreadyExists,
-            ErrorKind::WouldBlock => Code::Internal,
-            ErrorKind::InvalidInput => Code::InvalidArgument,
-            ErrorKind::InvalidData => Code::InvalidArgument,
-            ErrorKind::TimedOut => Code::DeadlineExceeded,
-            ErrorKind::WriteZero => Code::Internal,
-            ErrorKind::Interrupted => Code::Aborted,
-            ErrorKind::Other => Code::Internal,
-            ErrorKind::UnexpectedEof => Code::Internal,
-            _ => Code::Internal,
-        }
+    match kind {
+        ErrorKind::NotFound => Code::NotFound,
+        ErrorKind::PermissionDenied => Code::PermissionDenied,
+        ErrorKind::ConnectionRefused => Code::Unavailable,
+        ErrorKind::ConnectionReset => Code::Unavailable,
+        ErrorKind::ConnectionAborted => Code::Unavailable,
+        ErrorKind::NotConnected => Code::Internal,
+        ErrorKind::AddrInUse => Code::Internal,
+        ErrorKind::AddrNotAvailable => Code::Internal,
+        ErrorKind::BrokenPipe => Code::Internal,
+        ErrorKind::AlreadyExists => Code::AlreadyExists,
+        ErrorKind::WouldBlock => Code::Internal,
+        ErrorKind::InvalidInput => Code::InvalidArgument,
+        ErrorKind::InvalidData => Code::InvalidArgument,
+        ErrorKind::TimedOut => Code::DeadlineExceeded,
+        ErrorKind::WriteZero => Code::Internal,
+        ErrorKind::Interrupted => Code::Aborted,
+        ErrorKind::Other => Code::Internal,
+        ErrorKind::UnexpectedEof => Code::Internal,
+        _ => Code::Internal,
     }
+}
+
+fn result_to_status(result: Result<(), Error>) -> GrpcStatus {
     match result {
         Ok(()) => GrpcStatus {
             code: Code::Ok as i32,
@@ -63,19 +71,54 @@ fn result_to_status(result: Result<(), Error>) -> GrpcStatus {
     }
 }
 
+/// Converts a `Store` I/O error directly into a `tonic::Status`, for call
+/// sites that propagate the error with `?` instead of folding it into a
+/// per-entry response status (see `result_to_status` for that case).
+pub(crate) fn io_error_to_status(error: Error) -> Status {
+    Status::new(kind_to_code(&error.kind()), format!("Error: {:?}", error))
+}
+
+/// Number of `Store::has` lookups `find_missing_blobs` allows in flight at
+/// once, so `FindMissingBlobs` over many digests (the hot path Bazel hits
+/// before every action upload) runs up to this many lookups concurrently
+/// instead of one at a time.
+const FIND_MISSING_BLOBS_CONCURRENCY: usize = 100;
+
 #[derive(Debug)]
 pub struct CasServer {
-    pub store: Box<dyn Store>,
+    pub store: Arc<dyn Store>,
+    pub max_batch_total_size_bytes: usize,
 }
 
 impl CasServer {
-    pub fn new(store: Box<dyn Store>) -> Self {
-        CasServer { store: store }
+    pub fn new(store: Arc<dyn Store>, max_batch_total_size_bytes: usize) -> Self {
+        CasServer {
+            store,
+            max_batch_total_size_bytes,
+        }
     }
 
     pub fn into_service(self) -> Server<CasServer> {
         Server::new(self)
     }
+
+    /// Rejects batches whose combined blob sizes exceed the configured
+    /// `max_batch_total_size_bytes`, mirroring the limit advertised through
+    /// the `Capabilities` service.
+    fn check_batch_total_size(&self, total_size_bytes: u64) -> Result<(), Status> {
+        if self.max_batch_total_size_bytes > 0
+            && total_size_bytes > self.max_batch_total_size_bytes as u64
+        {
+            return Err(Status::new(
+                Code::InvalidArgument,
+                format!(
+                    "Batch total size of {} bytes exceeds max_batch_total_size_bytes of {} bytes",
+                    total_size_bytes, self.max_batch_total_size_bytes
+                ),
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[tonic::async_trait]
@@ -85,16 +128,25 @@ impl ContentAddressableStorage for CasServer {
         request: Request<FindMissingBlobsRequest>,
     ) -> Result<Response<FindMissingBlobsResponse>, Status> {
         let request_data = request.into_inner();
-        let mut response = FindMissingBlobsResponse {
-            missing_blob_digests: vec![],
-        };
-        for digest in request_data.blob_digests.into_iter() {
-            // BUG!!!!
-            if !self.store.has(&digest.hash, digest.hash.len()).await? {
-                response.missing_blob_digests.push(digest);
-            }
-        }
-        Ok(Response::new(response))
+        let store = &self.store;
+        let missing_blob_digests = stream::iter(request_data.blob_digests.into_iter())
+            .map(|digest| async move {
+                let size_bytes = usize::try_from(digest.size_bytes).map_err(|_| {
+                    Status::invalid_argument("Digest size_bytes was not convertable to usize")
+                })?;
+                let exists = store
+                    .has(&digest.hash, size_bytes)
+                    .await
+                    .map_err(io_error_to_status)?;
+                Ok::<_, Status>(if exists { None } else { Some(digest) })
+            })
+            .buffer_unordered(FIND_MISSING_BLOBS_CONCURRENCY)
+            .try_filter_map(|maybe_digest| async move { Ok(maybe_digest) })
+            .try_collect()
+            .await?;
+        Ok(Response::new(FindMissingBlobsResponse {
+            missing_blob_digests,
+        }))
     }
 
     async fn batch_update_blobs(
@@ -102,6 +154,13 @@ impl ContentAddressableStorage for CasServer {
         grpc_request: Request<BatchUpdateBlobsRequest>,
     ) -> Result<Response<BatchUpdateBlobsResponse>, Status> {
         let batch_request = grpc_request.into_inner();
+        let total_size_bytes: u64 = batch_request
+            .requests
+            .iter()
+            .map(|request| request.data.len() as u64)
+            .sum();
+        self.check_batch_total_size(total_size_bytes)?;
+
         let mut batch_response = BatchUpdateBlobsResponse {
             responses: Vec::with_capacity(batch_request.requests.len()),
         };
@@ -139,23 +198,110 @@ impl ContentAddressableStorage for CasServer {
 
     async fn batch_read_blobs(
         &self,
-        _request: Request<BatchReadBlobsRequest>,
+        grpc_request: Request<BatchReadBlobsRequest>,
     ) -> Result<Response<BatchReadBlobsResponse>, Status> {
-        use stdext::function_name;
-        let output = format!("{} not yet implemented", function_name!());
-        println!("{}", output);
-        Err(Status::unimplemented(output))
+        let batch_request = grpc_request.into_inner();
+        let total_size_bytes: u64 = batch_request
+            .digests
+            .iter()
+            .map(|digest| digest.size_bytes as u64)
+            .sum();
+        self.check_batch_total_size(total_size_bytes)?;
+
+        let mut batch_response = BatchReadBlobsResponse {
+            responses: Vec::with_capacity(batch_request.digests.len()),
+        };
+        for digest in batch_request.digests {
+            let orig_digest = digest.clone();
+            let mut data = Vec::new();
+            let result_status: Result<(), Error> = try {
+                let size_bytes = usize::try_from(digest.size_bytes).or_else(|_| {
+                    Err(make_err!("Digest size_bytes was not convertable to usize"))
+                })?;
+                self.store
+                    .get_part(&digest.hash, size_bytes, &mut data, 0, None)
+                    .await?;
+            };
+            let response = batch_read_blobs_response::Response {
+                digest: Some(orig_digest),
+                data,
+                status: Some(result_to_status(result_status)),
+            };
+            batch_response.responses.push(response);
+        }
+        Ok(Response::new(batch_response))
     }
 
     type GetTreeStream =
         Pin<Box<dyn Stream<Item = Result<GetTreeResponse, Status>> + Send + Sync + 'static>>;
     async fn get_tree(
         &self,
-        _request: Request<GetTreeRequest>,
+        grpc_request: Request<GetTreeRequest>,
     ) -> Result<Response<Self::GetTreeStream>, Status> {
-        use stdext::function_name;
-        let output = format!("{} not yet implemented", function_name!());
-        println!("{}", output);
-        Err(Status::unimplemented(output))
+        let get_tree_request = grpc_request.into_inner();
+        let root_digest = get_tree_request
+            .root_digest
+            .ok_or_else(|| Status::invalid_argument("GetTreeRequest is missing root_digest"))?;
+        let page_size = if get_tree_request.page_size > 0 {
+            get_tree_request.page_size as usize
+        } else {
+            usize::MAX
+        };
+        // We don't keep server-side cursor state, so the page token is just the
+        // number of directories already emitted by a previous call and we
+        // re-walk the tree (in the same deterministic BFS order) up to that point.
+        let skip = get_tree_request.page_token.parse::<usize>().unwrap_or(0);
+        let store = self.store.clone();
+
+        let output = try_stream! {
+            let mut to_visit: VecDeque<Digest> = VecDeque::new();
+            to_visit.push_back(root_digest);
+            let mut visited = HashSet::new();
+            let mut directories = Vec::new();
+            let mut visited_count = 0usize;
+            let mut emitted_count = skip;
+
+            while let Some(digest) = to_visit.pop_front() {
+                if !visited.insert(digest.hash.clone()) {
+                    continue;
+                }
+                let size_bytes = usize::try_from(digest.size_bytes)
+                    .map_err(|_| Status::invalid_argument("Digest size_bytes was not convertable to usize"))?;
+                let data = store
+                    .get(&digest.hash, size_bytes)
+                    .await
+                    .map_err(io_error_to_status)?;
+                let directory = Directory::decode(&data[..])
+                    .map_err(|e| Status::internal(format!("Failed to decode Directory: {:?}", e)))?;
+
+                for child in &directory.directories {
+                    if let Some(child_digest) = &child.digest {
+                        to_visit.push_back(child_digest.clone());
+                    }
+                }
+
+                visited_count += 1;
+                if visited_count <= skip {
+                    continue;
+                }
+                directories.push(directory);
+
+                if directories.len() >= page_size {
+                    emitted_count += directories.len();
+                    yield GetTreeResponse {
+                        directories: std::mem::take(&mut directories),
+                        next_page_token: emitted_count.to_string(),
+                    };
+                }
+            }
+
+            if !directories.is_empty() || emitted_count == skip {
+                yield GetTreeResponse {
+                    directories,
+                    next_page_token: String::new(),
+                };
+            }
+        };
+        Ok(Response::new(Box::pin(output)))
     }
 }
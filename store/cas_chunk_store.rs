@@ -0,0 +1,302 @@
+// Copyright 2020 Nathan (Blaise) Bruer.  All rights reserved.
+
+//! A `Store` wrapper that splits large blobs into content-defined chunks
+//! before handing them to an inner store, so two blobs that only differ by a
+//! small local edit (eg. a slightly changed tarball) end up sharing most of
+//! their chunks instead of being stored twice in full.
+//!
+//! The digest a caller uses to `has`/`get`/`update` a blob is completely
+//! unchanged by this wrapper; chunking is an implementation detail of how the
+//! bytes behind that digest are physically stored.
+
+use std::io::{Cursor, Read, Write};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use sha2::{Digest as _, Sha256};
+use tokio::io::{Error, ErrorKind};
+
+use crate::fast_cdc::FastCdc;
+use crate::store_trait::Store;
+
+struct ChunkEntry {
+    hash: String,
+    size: usize,
+}
+
+/// Wraps another `Store` with a content-defined chunking layer.
+#[derive(Debug)]
+pub struct CasChunkStore {
+    inner_store: Arc<dyn Store>,
+}
+
+impl CasChunkStore {
+    pub fn new(inner_store: Arc<dyn Store>) -> Self {
+        CasChunkStore { inner_store }
+    }
+
+    fn hash_chunk(data: &[u8]) -> String {
+        let digest = Sha256::digest(data);
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn encode_index(entries: &[ChunkEntry]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for entry in entries {
+            out.extend_from_slice(entry.hash.as_bytes());
+            out.push(b' ');
+            out.extend_from_slice(entry.size.to_string().as_bytes());
+            out.push(b'\n');
+        }
+        out
+    }
+
+    fn decode_index(data: &[u8]) -> Result<Vec<ChunkEntry>, Error> {
+        std::str::from_utf8(data)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?
+            .lines()
+            .map(|line| {
+                let (hash, size) = line
+                    .rsplit_once(' ')
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Malformed chunk index entry"))?;
+                let size = size
+                    .parse::<usize>()
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                Ok(ChunkEntry {
+                    hash: hash.to_string(),
+                    size,
+                })
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Store for CasChunkStore {
+    async fn has(&self, hash: &str, size_bytes: usize) -> Result<bool, Error> {
+        self.inner_store.has(hash, size_bytes).await
+    }
+
+    async fn update(
+        &self,
+        hash: &str,
+        size_bytes: usize,
+        mut reader: Box<dyn Read + Send>,
+    ) -> Result<(), Error> {
+        let mut data = Vec::with_capacity(size_bytes);
+        reader.read_to_end(&mut data)?;
+
+        let chunker = FastCdc::with_default_sizes();
+        let mut entries = Vec::new();
+        for (offset, length) in chunker.chunks(&data) {
+            let chunk = &data[offset..offset + length];
+            let chunk_hash = Self::hash_chunk(chunk);
+            if !self.inner_store.has(&chunk_hash, length).await? {
+                self.inner_store
+                    .update(&chunk_hash, length, Box::new(Cursor::new(chunk.to_vec())))
+                    .await?;
+            }
+            entries.push(ChunkEntry {
+                hash: chunk_hash,
+                size: length,
+            });
+        }
+
+        let index = Self::encode_index(&entries);
+        self.inner_store
+            .update(hash, size_bytes, Box::new(Cursor::new(index)))
+            .await
+    }
+
+    async fn get_part(
+        &self,
+        hash: &str,
+        size_bytes: usize,
+        writer: &mut (dyn Write + Send),
+        offset: usize,
+        length: Option<usize>,
+    ) -> Result<(), Error> {
+        // The blob stored under `hash`/`size_bytes` is the chunk index, whose
+        // real length has nothing to do with `size_bytes` (the *outer*,
+        // caller-supplied blob size), so it is read whole with `length: None`.
+        let mut index_data = Vec::new();
+        self.inner_store
+            .get_part(hash, size_bytes, &mut index_data, 0, None)
+            .await?;
+        let entries = Self::decode_index(&index_data)?;
+
+        let end = length.map(|len| offset + len);
+        let mut chunk_start = 0;
+        for entry in entries {
+            let chunk_end = chunk_start + entry.size;
+            let overlaps_start = end.map_or(true, |end| chunk_start < end);
+            if chunk_end > offset && overlaps_start {
+                let chunk = self.inner_store.get(&entry.hash, entry.size).await?;
+                let slice_start = offset.saturating_sub(chunk_start).min(chunk.len());
+                let slice_end = end
+                    .map(|end| (end - chunk_start).min(chunk.len()))
+                    .unwrap_or(chunk.len());
+                writer.write_all(&chunk[slice_start..slice_end])?;
+            }
+            chunk_start = chunk_end;
+            if let Some(end) = end {
+                if chunk_start >= end {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Minimal in-memory `Store` used only to exercise `CasChunkStore`
+    /// without needing a real backend.
+    #[derive(Debug, Default)]
+    struct MemoryStore {
+        blobs: Mutex<HashMap<(String, usize), Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl Store for MemoryStore {
+        async fn has(&self, hash: &str, size_bytes: usize) -> Result<bool, Error> {
+            Ok(self
+                .blobs
+                .lock()
+                .unwrap()
+                .contains_key(&(hash.to_string(), size_bytes)))
+        }
+
+        async fn update(
+            &self,
+            hash: &str,
+            size_bytes: usize,
+            mut reader: Box<dyn Read + Send>,
+        ) -> Result<(), Error> {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            self.blobs
+                .lock()
+                .unwrap()
+                .insert((hash.to_string(), size_bytes), data);
+            Ok(())
+        }
+
+        async fn get_part(
+            &self,
+            hash: &str,
+            size_bytes: usize,
+            writer: &mut (dyn Write + Send),
+            offset: usize,
+            length: Option<usize>,
+        ) -> Result<(), Error> {
+            let blobs = self.blobs.lock().unwrap();
+            let data = blobs
+                .get(&(hash.to_string(), size_bytes))
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, "blob not found"))?;
+            let start = offset.min(data.len());
+            let end = length.map_or(data.len(), |len| (offset + len).min(data.len()));
+            writer.write_all(&data[start..end])
+        }
+    }
+
+    fn pseudo_random_bytes(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        let mut out = Vec::with_capacity(len);
+        for _ in 0..len {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            out.push((state & 0xff) as u8);
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn round_trips_a_multi_chunk_blob() {
+        let chunk_store = CasChunkStore::new(Arc::new(MemoryStore::default()));
+        let data = pseudo_random_bytes(3 * 1024 * 1024, 0x0102_0304_0506_0708);
+        let hash = "deadbeef";
+        let size_bytes = data.len();
+
+        chunk_store
+            .update(hash, size_bytes, Box::new(Cursor::new(data.clone())))
+            .await
+            .unwrap();
+
+        assert!(chunk_store.has(hash, size_bytes).await.unwrap());
+        assert_eq!(chunk_store.get(hash, size_bytes).await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn get_part_reassembles_an_arbitrary_window_across_chunk_boundaries() {
+        let chunk_store = CasChunkStore::new(Arc::new(MemoryStore::default()));
+        let data = pseudo_random_bytes(2 * 1024 * 1024, 0x90ab_cdef_1122_3344);
+        let hash = "feedface";
+        let size_bytes = data.len();
+        chunk_store
+            .update(hash, size_bytes, Box::new(Cursor::new(data.clone())))
+            .await
+            .unwrap();
+
+        let offset = 300_000;
+        let length = 500_000;
+        let mut out = Vec::new();
+        chunk_store
+            .get_part(hash, size_bytes, &mut out, offset, Some(length))
+            .await
+            .unwrap();
+
+        assert_eq!(out, data[offset..offset + length]);
+    }
+
+    #[tokio::test]
+    async fn identical_chunks_are_deduplicated_in_the_inner_store() {
+        let inner = Arc::new(MemoryStore::default());
+        let chunk_store = CasChunkStore::new(inner.clone());
+        // Two blobs sharing a long common prefix should end up storing that
+        // shared region's chunks only once in the inner store.
+        let common = pseudo_random_bytes(2 * 1024 * 1024, 0xaaaa_bbbb_cccc_dddd);
+        let mut first = common.clone();
+        first.extend(pseudo_random_bytes(10_000, 1));
+        let mut second = common.clone();
+        second.extend(pseudo_random_bytes(10_000, 2));
+
+        chunk_store
+            .update("first", first.len(), Box::new(Cursor::new(first.clone())))
+            .await
+            .unwrap();
+        let blobs_after_first = inner.blobs.lock().unwrap().len();
+
+        chunk_store
+            .update(
+                "second",
+                second.len(),
+                Box::new(Cursor::new(second.clone())),
+            )
+            .await
+            .unwrap();
+        let blobs_after_second = inner.blobs.lock().unwrap().len();
+
+        // The second update adds its own index entry plus only the chunks
+        // that differ from the first blob, not a full second copy.
+        let second_chunk_count = CasChunkStore::decode_index(
+            &inner
+                .blobs
+                .lock()
+                .unwrap()
+                .get(&("second".to_string(), second.len()))
+                .unwrap()
+                .clone(),
+        )
+        .unwrap()
+        .len();
+        assert!(blobs_after_second - blobs_after_first < second_chunk_count + 1);
+    }
+}
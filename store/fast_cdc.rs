@@ -0,0 +1,166 @@
+// Copyright 2020 Nathan (Blaise) Bruer.  All rights reserved.
+
+//! A FastCDC-style content-defined chunker.
+//!
+//! Boundaries are chosen from a rolling Gear hash so that a small edit to the
+//! input only perturbs the chunks touching the edit, instead of reshuffling
+//! every chunk after it the way fixed-size slicing would.
+
+/// Randomly generated 256-entry Gear hash table. Any full-rank table works;
+/// this one is just a fixed, reproducible set of 64-bit constants.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut i = 0;
+    while i < 256 {
+        // A cheap xorshift64* is enough to spread the table; we don't need
+        // cryptographic quality, just low collision rates across bytes.
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        table[i] = state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        i += 1;
+    }
+    table
+};
+
+pub struct FastCdc {
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+    mask_small: u64,
+    mask_large: u64,
+}
+
+impl FastCdc {
+    /// `avg_size` should be a power of two; the small/large masks are derived
+    /// from it so that boundaries land on average every `avg_size` bytes,
+    /// while `min_size`/`max_size` clamp how far a boundary can drift.
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> Self {
+        let bits = (avg_size.max(2) as f64).log2().round() as u32;
+        FastCdc {
+            min_size,
+            avg_size,
+            max_size,
+            mask_small: (1u64 << bits.saturating_sub(1)) - 1,
+            mask_large: (1u64 << bits.saturating_add(1)) - 1,
+        }
+    }
+
+    pub fn with_default_sizes() -> Self {
+        // 256 KiB average, 64 KiB minimum, 1 MiB maximum.
+        Self::new(64 * 1024, 256 * 1024, 1024 * 1024)
+    }
+
+    /// Splits `data` into content-defined chunks, returning `(offset, length)`
+    /// pairs covering the whole input in order.
+    pub fn chunks(&self, data: &[u8]) -> Vec<(usize, usize)> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        while start < data.len() {
+            let end = self.next_boundary(&data[start..]);
+            chunks.push((start, end));
+            start += end;
+        }
+        chunks
+    }
+
+    /// Finds the length of the next chunk starting at the beginning of
+    /// `data`, which may be shorter than the full slice.
+    fn next_boundary(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+        let max = self.max_size.min(data.len());
+        let mut hash: u64 = 0;
+        let mut i = self.min_size;
+        // Use the tighter mask while under the average size, then relax it
+        // so chunks don't keep growing forever in low-entropy data.
+        while i < max {
+            hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+            let mask = if i < self.avg_size {
+                self.mask_large
+            } else {
+                self.mask_small
+            };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+            i += 1;
+        }
+        max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic xorshift64* byte generator so tests don't need a `rand`
+    /// dependency; the same seed always produces the same bytes.
+    fn pseudo_random_bytes(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        let mut out = Vec::with_capacity(len);
+        for _ in 0..len {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            out.push((state & 0xff) as u8);
+        }
+        out
+    }
+
+    #[test]
+    fn chunk_lengths_respect_min_and_max_and_cover_the_input() {
+        let cdc = FastCdc::new(16, 64, 256);
+        let data = pseudo_random_bytes(10_000, 0x1234_5678_9abc_def0);
+        let chunks = cdc.chunks(&data);
+
+        let total: usize = chunks.iter().map(|&(_, len)| len).sum();
+        assert_eq!(total, data.len());
+
+        for (i, &(_, len)) in chunks.iter().enumerate() {
+            assert!(len <= 256, "chunk {} exceeded max_size: {}", i, len);
+            // Only the final chunk is allowed to fall under min_size.
+            if i + 1 != chunks.len() {
+                assert!(len >= 16, "chunk {} was below min_size: {}", i, len);
+            }
+        }
+    }
+
+    #[test]
+    fn low_entropy_input_still_respects_max_size() {
+        // All-zero input never satisfies `hash & mask == 0` via content
+        // variation, so every chunk should be clamped at max_size.
+        let cdc = FastCdc::new(16, 64, 256);
+        let data = vec![0u8; 10_000];
+        let chunks = cdc.chunks(&data);
+        for &(_, len) in &chunks {
+            assert!(len <= 256);
+        }
+    }
+
+    #[test]
+    fn single_byte_edit_leaves_chunks_before_it_unchanged() {
+        let cdc = FastCdc::new(16, 64, 256);
+        let mut data = pseudo_random_bytes(10_000, 0xdead_beef_1234_5678);
+        let original_chunks = cdc.chunks(&data);
+
+        let edit_offset = data.len() / 2;
+        data[edit_offset] ^= 0xff;
+        let edited_chunks = cdc.chunks(&data);
+
+        // Chunks entirely before the edited byte shouldn't move at all --
+        // that's the whole point of content-defined (vs. fixed-size)
+        // chunking.
+        let unchanged_prefix_chunks = original_chunks
+            .iter()
+            .zip(edited_chunks.iter())
+            .take_while(|(a, b)| a == b && a.0 + a.1 <= edit_offset)
+            .count();
+        assert!(
+            unchanged_prefix_chunks > 0,
+            "expected at least one chunk boundary before the edit to be stable"
+        );
+    }
+}
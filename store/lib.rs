@@ -0,0 +1,10 @@
+// Copyright 2020 Nathan (Blaise) Bruer.  All rights reserved.
+
+mod cas_chunk_store;
+mod fast_cdc;
+mod object_store;
+mod store_trait;
+
+pub use cas_chunk_store::CasChunkStore;
+pub use object_store::ObjectStore;
+pub use store_trait::Store;
@@ -0,0 +1,169 @@
+// Copyright 2020 Nathan (Blaise) Bruer.  All rights reserved.
+
+//! A `Store` backed by the `object_store` crate, so blobs can be persisted
+//! to S3, GCS, Azure Blob Storage, or a local directory behind a single
+//! configuration URL (eg. `s3://bucket/prefix`, `file:///var/cache/cas`).
+//! This turns the CAS into a durable cache that can be shared across
+//! machines instead of living only in one server's memory.
+
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use object_store::path::Path as ObjectPath;
+use object_store::{Error as ObjectStoreError, ObjectStore as ObjectStoreBackend};
+use tokio::io::{Error, ErrorKind};
+use url::Url;
+
+use crate::store_trait::Store;
+
+/// Number of times a transient remote error is retried before being
+/// surfaced to the caller.
+const MAX_RETRIES: usize = 4;
+/// Base delay for the exponential backoff between retries.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// A `Store` implementation that maps each blob's hash to an object key in
+/// a remote (or local-filesystem) object store.
+#[derive(Debug)]
+pub struct ObjectStore {
+    backend: Box<dyn ObjectStoreBackend>,
+    key_prefix: ObjectPath,
+}
+
+impl ObjectStore {
+    /// Builds an `ObjectStore` from a URL like `s3://bucket/prefix`,
+    /// `gs://bucket/prefix`, `azure://container/prefix`, or
+    /// `file:///var/cache/cas`.
+    pub fn new(url: &str) -> Result<Self, Error> {
+        let parsed_url = Url::parse(url)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Invalid store url: {:?}", e)))?;
+        let (backend, key_prefix) = object_store::parse_url(&parsed_url)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Could not construct object store: {:?}", e)))?;
+        Ok(ObjectStore { backend, key_prefix })
+    }
+
+    fn object_path(&self, hash: &str, size_bytes: usize) -> ObjectPath {
+        self.key_prefix.child(format!("{}-{}", hash, size_bytes))
+    }
+
+    /// Runs `op` and retries transient failures up to `MAX_RETRIES` times
+    /// with exponential backoff before giving up, so a blip in connectivity to the remote store doesn't fail
+    /// the whole request.
+    async fn retry<T, F, Fut>(&self, mut op: F) -> Result<T, ObjectStoreError>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T, ObjectStoreError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match op().await {
+                Ok(value) => return Ok(value),
+                Err(err) if attempt < MAX_RETRIES && is_transient(&err) => {
+                    tokio::time::sleep(RETRY_BASE_DELAY * 2u32.pow(attempt as u32)).await;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Errors worth retrying: anything that looks like a transport hiccup rather
+/// than a permanent condition (not found, permission denied, bad input).
+fn is_transient(err: &ObjectStoreError) -> bool {
+    !matches!(
+        err,
+        ObjectStoreError::NotFound { .. }
+            | ObjectStoreError::AlreadyExists { .. }
+            | ObjectStoreError::InvalidPath { .. }
+            | ObjectStoreError::PermissionDenied { .. }
+    )
+}
+
+fn to_io_error(err: ObjectStoreError) -> Error {
+    match err {
+        ObjectStoreError::NotFound { .. } => Error::new(ErrorKind::NotFound, err),
+        ObjectStoreError::PermissionDenied { .. } => Error::new(ErrorKind::PermissionDenied, err),
+        ObjectStoreError::AlreadyExists { .. } => Error::new(ErrorKind::AlreadyExists, err),
+        // Everything else, including transient errors whose retries ran out,
+        // is surfaced as Unavailable (-> Code::Unavailable via result_to_status).
+        _ => Error::new(ErrorKind::ConnectionAborted, err),
+    }
+}
+
+#[async_trait]
+impl Store for ObjectStore {
+    async fn has(&self, hash: &str, size_bytes: usize) -> Result<bool, Error> {
+        let path = self.object_path(hash, size_bytes);
+        match self.retry(|| self.backend.head(&path)).await {
+            Ok(_) => Ok(true),
+            Err(ObjectStoreError::NotFound { .. }) => Ok(false),
+            Err(e) => Err(to_io_error(e)),
+        }
+    }
+
+    async fn update(
+        &self,
+        hash: &str,
+        size_bytes: usize,
+        mut reader: Box<dyn Read + Send>,
+    ) -> Result<(), Error> {
+        // Don't preallocate from `size_bytes`: it's caller supplied and, per
+        // the Store trait contract, isn't guaranteed to match the reader's
+        // real length (eg. CasChunkStore writes a small index blob under the
+        // full logical blob size) -- let the buffer grow with what's read.
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let path = self.object_path(hash, size_bytes);
+        let bytes = Bytes::from(data);
+        self.retry(|| self.backend.put(&path, bytes.clone().into()))
+            .await
+            .map(|_| ())
+            .map_err(to_io_error)
+    }
+
+    async fn get_part(
+        &self,
+        hash: &str,
+        size_bytes: usize,
+        writer: &mut (dyn Write + Send),
+        offset: usize,
+        length: Option<usize>,
+    ) -> Result<(), Error> {
+        let path = self.object_path(hash, size_bytes);
+        let bytes = match length {
+            Some(len) => {
+                let end = offset + len;
+                self.retry(|| self.backend.get_range(&path, offset..end))
+                    .await
+                    .map_err(to_io_error)?
+            }
+            // `size_bytes` is only part of the object key here, not a
+            // guarantee of the object's physical length (eg. `CasChunkStore`
+            // stores a small chunk index under the logical blob's
+            // size_bytes). A whole-object read must not assume `size_bytes`
+            // bounds the real content, so fetch the object directly instead
+            // of range-reading `0..size_bytes`.
+            None if offset == 0 => self
+                .retry(|| self.backend.get(&path))
+                .await
+                .map_err(to_io_error)?
+                .bytes()
+                .await
+                .map_err(to_io_error)?,
+            None => {
+                let whole = self
+                    .retry(|| self.backend.get(&path))
+                    .await
+                    .map_err(to_io_error)?
+                    .bytes()
+                    .await
+                    .map_err(to_io_error)?;
+                whole.slice(offset.min(whole.len())..)
+            }
+        };
+        writer.write_all(&bytes)
+    }
+}
@@ -0,0 +1,59 @@
+// Copyright 2020 Nathan (Blaise) Bruer.  All rights reserved.
+
+use std::fmt::Debug;
+use std::io::{Read, Write};
+
+use async_trait::async_trait;
+use tokio::io::Error;
+
+/// Generic interface for storing and retrieving content addressed blobs.
+///
+/// Implementations are free to back this with memory, disk, or a remote
+/// object store, as long as a blob written with a given `hash`/`size_bytes`
+/// can be read back with the same `hash`/`size_bytes`.
+///
+/// `size_bytes` is part of the blob's key alongside `hash`, not a separately
+/// trustworthy content-length: a wrapping `Store` (eg. a chunking layer) may
+/// store bytes under a given `hash`/`size_bytes` whose physical length
+/// differs from `size_bytes`. A `get_part`/`get` call with `length: None`
+/// must return the *whole* stored blob regardless of whether its actual
+/// length matches `size_bytes`.
+#[async_trait]
+pub trait Store: Sync + Send + Debug {
+    /// Returns true if a blob with the given hash and size is present.
+    async fn has(&self, hash: &str, size_bytes: usize) -> Result<bool, Error>;
+
+    /// Stores the contents read from `reader` under `hash`/`size_bytes`.
+    async fn update(
+        &self,
+        hash: &str,
+        size_bytes: usize,
+        reader: Box<dyn Read + Send>,
+    ) -> Result<(), Error>;
+
+    /// Writes `length` bytes (or to the end of the blob if `None`) starting
+    /// at `offset` of the blob identified by `hash`/`size_bytes` into `writer`.
+    async fn get_part(
+        &self,
+        hash: &str,
+        size_bytes: usize,
+        writer: &mut (dyn Write + Send),
+        offset: usize,
+        length: Option<usize>,
+    ) -> Result<(), Error>;
+
+    /// Convenience wrapper around `get_part` that reads the whole blob into
+    /// memory.
+    ///
+    /// Deliberately does not preallocate based on `size_bytes`: it's caller
+    /// supplied and, per the contract above, isn't guaranteed to match the
+    /// blob's real length, so `Vec::with_capacity(size_bytes)` here would let
+    /// an oversized declared size abort the process via `handle_alloc_error`
+    /// before any I/O (or existence check) happens. Let the buffer grow with
+    /// the data actually read instead.
+    async fn get(&self, hash: &str, size_bytes: usize) -> Result<Vec<u8>, Error> {
+        let mut data = Vec::new();
+        self.get_part(hash, size_bytes, &mut data, 0, None).await?;
+        Ok(data)
+    }
+}